@@ -0,0 +1,71 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! `finshirctl` -- a tiny companion client for a running `finshir` instance's
+//! control socket (see `src/control.rs`). Connects, sends one command, prints
+//! whatever the instance writes back, and exits.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    author = "Temirkhan Myrzamadi <gymmasssorla@gmail.com>",
+    about = "A control client for a running finshir instance",
+    set_term_width = 80
+)]
+struct Args {
+    /// The control socket of the finshir instance to talk to, as given to
+    /// its own `--control-socket` option
+    #[structopt(short = "s", long = "socket", takes_value = true, value_name = "LOCATION")]
+    socket: PathBuf,
+
+    /// The command to issue: `stats`, `pause`, `resume`, or
+    /// `set-periodicity <TIME-SPAN>`
+    #[structopt(required = true)]
+    command: Vec<String>,
+}
+
+fn main() {
+    let args = Args::from_args();
+    let command = args.command.join(" ");
+
+    let mut stream = UnixStream::connect(&args.socket).unwrap_or_else(|err| {
+        eprintln!(
+            "Failed to connect the control socket at {:?} >>> {}!",
+            args.socket, err
+        );
+        std::process::exit(1);
+    });
+
+    if let Err(err) = writeln!(stream, "{}", command) {
+        eprintln!("Failed to send the command >>> {}!", err);
+        std::process::exit(1);
+    }
+
+    let mut response = String::new();
+    if let Err(err) = BufReader::new(stream).read_line(&mut response) {
+        eprintln!("Failed to read the response >>> {}!", err);
+        std::process::exit(1);
+    }
+
+    print!("{}", response);
+}
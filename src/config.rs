@@ -18,7 +18,8 @@
 
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
-use std::net::SocketAddr;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::num::{NonZeroUsize, ParseIntError};
 use std::path::PathBuf;
 use std::time::Duration;
@@ -46,8 +47,11 @@ pub struct ArgsConfig {
     )]
     pub wait: Duration,
 
-    /// A location to a file consisting of a single JSON array of data portions,
-    /// specified as strings.
+    /// A location to a file consisting of a single JSON array of data portions.
+    ///
+    /// Every element is either a plain string or a tagged object --
+    /// `{"base64": "..."}`, `{"hex": "..."}`, or `{"bytes": [..]}` -- for
+    /// portions that need raw binary content.
     ///
     /// If an amount of data portions is reached on a certain connection, a
     /// connection will be reopened.
@@ -75,6 +79,20 @@ pub struct ArgsConfig {
     )]
     pub connections: NonZeroUsize,
 
+    /// A Unix domain socket the running instance listens on for runtime
+    /// control commands (`stats`, `pause`, `resume`, `set-periodicity
+    /// <TIME-SPAN>`), issued with the `finshirctl` companion. Left unset by
+    /// default, which disables the control subsystem entirely.
+    #[structopt(
+        long = "control-socket",
+        takes_value = true,
+        value_name = "LOCATION"
+    )]
+    pub control_socket: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub reporting_config: ReportingConfig,
+
     #[structopt(flatten)]
     pub tester_config: TesterConfig,
 
@@ -95,10 +113,8 @@ pub struct SocketConfig {
     pub receiver: SocketAddr,
 
     /// If a timeout is reached and a socket wasn't connected, the program will
-    /// retry the operation later.
-    ///
-    /// Note that this option currently doesn't work for sockets which are
-    /// trying to connect through Tor.
+    /// retry the operation later. Applies uniformly to regular sockets and to
+    /// sockets connecting through Tor.
     #[structopt(
         long = "connect-timeout",
         takes_value = true,
@@ -119,13 +135,131 @@ pub struct SocketConfig {
     )]
     pub write_timeout: Duration,
 
-    /// Connect all future sockets to a local Tor proxy, specified as an IP
-    /// address and a port number, separated by a colon.
+    /// Connect all future sockets through an in-process Tor client (arti),
+    /// building circuits locally instead of talking to a separate Tor daemon
+    #[structopt(long = "use-tor")]
+    pub use_tor: bool,
+
+    /// A bridge line to seed the in-process Tor client with, in the same
+    /// format as `torrc`. May be specified multiple times.
+    ///
+    /// Has no effect unless `--use-tor` is also specified.
+    #[structopt(
+        long = "tor-bridge",
+        takes_value = true,
+        value_name = "BRIDGE-LINE",
+        requires = "use_tor"
+    )]
+    pub tor_bridges: Vec<String>,
+
+    /// Build a dedicated circuit for every coroutine instead of sharing
+    /// circuits between connections, so a single exit node being blocked
+    /// can't kill the whole run.
     ///
-    /// Typically, a Tor proxy runs on 127.0.0.1:9050. You can edit its
-    /// configuration located in `/etc/tor/torrc`.
-    #[structopt(long = "tor-proxy", takes_value = true, value_name = "SOCKET-ADDRESS")]
-    pub tor_proxy: Option<SocketAddr>,
+    /// Has no effect unless `--use-tor` is also specified.
+    #[structopt(long = "tor-isolate-circuits", requires = "use_tor")]
+    pub tor_isolate_circuits: bool,
+
+    /// Sets the IP_TTL value for every spawned socket
+    #[structopt(long = "ip-ttl", takes_value = true, value_name = "NUMBER")]
+    pub ip_ttl: Option<u32>,
+
+    /// Wrap every connection in a TLS session using rustls, so the tool can
+    /// attack HTTPS receivers which refuse plaintext traffic outright
+    #[structopt(long = "tls")]
+    pub tls: bool,
+
+    /// Overrides the TLS server name used for the SNI extension and
+    /// certificate verification. Defaults to the receiver's IP address,
+    /// which most HTTPS servers reject, so set this when the receiver is
+    /// fronted by name-based virtual hosting.
+    ///
+    /// Has no effect unless `--tls` is also specified.
+    #[structopt(
+        long = "sni",
+        takes_value = true,
+        value_name = "HOSTNAME",
+        requires = "tls"
+    )]
+    pub sni: Option<String>,
+
+    /// Skip TLS certificate verification entirely.
+    ///
+    /// Only use this against receivers presenting self-signed or otherwise
+    /// untrusted certificates. Has no effect unless `--tls` is specified.
+    #[structopt(long = "insecure", requires = "tls")]
+    pub insecure: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, Eq, PartialEq)]
+pub struct Http2Config {
+    /// Negotiate HTTP/2 (over TLS via ALPN, or h2c in plaintext) and run the
+    /// stream-level slow-read attack instead of the byte-stream tester: open
+    /// many concurrent streams with a tiny flow-control window, then trickle
+    /// minimal WINDOW_UPDATE frames to keep every one of them stalled
+    /// indefinitely
+    #[structopt(long = "http2")]
+    pub http2: bool,
+
+    /// The initial flow-control window size, in bytes, advertised for both
+    /// the connection and every stream. Kept tiny on purpose, so the server
+    /// fills it almost immediately and is left waiting on us.
+    ///
+    /// Has no effect unless `--http2` is also specified.
+    #[structopt(
+        long = "http2-initial-window-size",
+        takes_value = true,
+        value_name = "BYTES",
+        default_value = "1",
+        requires = "http2"
+    )]
+    pub initial_window_size: u32,
+}
+
+#[derive(StructOpt, Debug, Clone, Eq, PartialEq)]
+pub struct ReportingConfig {
+    /// How often the aggregated live statistics (active connections, bytes
+    /// sent, successful/failed sends, reconnects) are logged as a summary
+    /// line, and, if `--report-endpoint` is given, pushed to it
+    #[structopt(
+        long = "report-interval",
+        takes_value = true,
+        value_name = "TIME-SPAN",
+        default_value = "30secs",
+        parse(try_from_str = "parse_duration")
+    )]
+    pub report_interval: Duration,
+
+    /// An HTTP endpoint (`http://host:port/path`) that periodically receives
+    /// a small JSON record of the live statistics. Left unset by default,
+    /// which keeps reporting local to the summary log line.
+    #[structopt(long = "report-endpoint", takes_value = true, value_name = "URL")]
+    pub report_endpoint: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, Eq, PartialEq)]
+pub struct HooksConfig {
+    /// A program spawned every time a coroutine successfully connects a
+    /// brand new socket for the first time
+    #[structopt(long = "hook-on-connect", takes_value = true, value_name = "PROGRAM")]
+    pub on_connect: Option<PathBuf>,
+
+    /// A program spawned every time a coroutine reconnects a socket after
+    /// the previous one was dropped (for example, after `failed_count`
+    /// failed writes in a row)
+    #[structopt(
+        long = "hook-on-reconnect",
+        takes_value = true,
+        value_name = "PROGRAM"
+    )]
+    pub on_reconnect: Option<PathBuf>,
+
+    /// A program spawned every time a coroutine exits because the allotted
+    /// `--test-duration` has elapsed (running out of data portions reconnects
+    /// the socket instead). With `--http2`, it is spawned every time an
+    /// HTTP/2 connection closes
+    #[structopt(long = "hook-on-exit", takes_value = true, value_name = "PROGRAM")]
+    pub on_exit: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug, Clone, Eq, PartialEq)]
@@ -152,8 +286,33 @@ pub struct TesterConfig {
     )]
     pub failed_count: NonZeroUsize,
 
+    /// Treat every data portion as a template that may embed `{...}`
+    /// placeholders (e.g. `{random_int(1, 65535)}`, `{uuid()}`, `{conn_id}`)
+    /// evaluated fresh for every coroutine and every send, so connections no
+    /// longer emit byte-identical traffic. In this mode every element of the
+    /// portions file must be a plain string; tagged objects are rejected
+    #[structopt(long = "template")]
+    pub template_mode: bool,
+
+    /// A time span after which every coroutine stops sending data and exits,
+    /// regardless of how many data portions are left
+    #[structopt(
+        long = "test-duration",
+        takes_value = true,
+        value_name = "TIME-SPAN",
+        default_value = "64min",
+        parse(try_from_str = "parse_duration")
+    )]
+    pub test_duration: Duration,
+
     #[structopt(flatten)]
     pub socket_config: SocketConfig,
+
+    #[structopt(flatten)]
+    pub http2_config: Http2Config,
+
+    #[structopt(flatten)]
+    pub hooks_config: HooksConfig,
 }
 
 #[derive(StructOpt, Debug, Clone, Eq, PartialEq)]
@@ -188,8 +347,122 @@ pub struct LoggingConfig {
         parse(try_from_str = "parse_time_format")
     )]
     pub date_time_format: String,
+
+    /// Ship log records to a syslog collector in parallel with the console
+    /// output. Accepts `none` (the default), a path to a local Unix domain
+    /// socket such as `/dev/log`, or a remote `udp://host:port` /
+    /// `tcp://host:port` endpoint
+    #[structopt(
+        long = "syslog-target",
+        takes_value = true,
+        value_name = "none|PATH|udp://HOST:PORT|tcp://HOST:PORT",
+        default_value = "none",
+        parse(try_from_str = "parse_syslog_target")
+    )]
+    pub syslog_target: SyslogTarget,
+
+    /// The console output format. `console` produces the usual colored `[level]
+    /// [time]: message` line; `json` emits one NDJSON object per line
+    /// (`level`, `timestamp`, `message`, plus whatever context the record
+    /// carries), for piping into log processors and dashboards
+    #[structopt(
+        long = "log-format",
+        takes_value = true,
+        value_name = "FORMAT",
+        default_value = "console",
+        possible_value = "console",
+        possible_value = "json",
+        parse(try_from_str = "parse_log_format")
+    )]
+    pub log_format: LogFormat,
+}
+
+/// The console output format selected by `--log-format`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LogFormat {
+    Console,
+    Json,
+}
+
+pub fn parse_log_format(format: &str) -> Result<LogFormat, LogFormatError> {
+    match format {
+        "console" => Ok(LogFormat::Console),
+        "json" => Ok(LogFormat::Json),
+        other => Err(LogFormatError::Unknown(other.to_owned())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormatError {
+    Unknown(String),
+}
+
+impl Display for LogFormatError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            LogFormatError::Unknown(format) => {
+                write!(fmt, "'{}' is not a valid log format", format)
+            }
+        }
+    }
+}
+
+impl Error for LogFormatError {}
+
+/// Where (if anywhere) log records get mirrored to, as a syslog collector.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SyslogTarget {
+    None,
+    Unix(PathBuf),
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+pub fn parse_syslog_target(target: &str) -> Result<SyslogTarget, SyslogTargetError> {
+    if target == "none" {
+        Ok(SyslogTarget::None)
+    } else if let Some(addr) = target.strip_prefix("udp://") {
+        resolve_socket_addr(addr).map(SyslogTarget::Udp)
+    } else if let Some(addr) = target.strip_prefix("tcp://") {
+        resolve_socket_addr(addr).map(SyslogTarget::Tcp)
+    } else {
+        Ok(SyslogTarget::Unix(PathBuf::from(target)))
+    }
 }
 
+// Resolves `addr` (`HOST:PORT`) to a single `SocketAddr`, via the system
+// resolver rather than `SocketAddr::parse`, since the latter only accepts
+// literal IPs -- and `--syslog-target`'s own help text promises a hostname
+// works just as well as an IP here.
+fn resolve_socket_addr(addr: &str) -> Result<SocketAddr, SyslogTargetError> {
+    addr.to_socket_addrs()
+        .map_err(|err| SyslogTargetError::InvalidAddress(addr.to_owned(), err))?
+        .next()
+        .ok_or_else(|| {
+            SyslogTargetError::InvalidAddress(
+                addr.to_owned(),
+                io::Error::new(io::ErrorKind::InvalidInput, "no addresses resolved"),
+            )
+        })
+}
+
+#[derive(Debug)]
+pub enum SyslogTargetError {
+    InvalidAddress(String, io::Error),
+}
+
+impl Display for SyslogTargetError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            SyslogTargetError::InvalidAddress(addr, err) => {
+                write!(fmt, "could not resolve '{}' >>> {}", addr, err)
+            }
+        }
+    }
+}
+
+impl Error for SyslogTargetError {}
+
 pub fn parse_time_format(format: &str) -> Result<String, time::ParseError> {
     // If the `strftime` call succeeds, then the format is correct
     time::strftime(format, &time::now())?;
@@ -290,4 +563,48 @@ mod tests {
 
         assert_eq!(parse_non_zero_usize("0"), Err(NonZeroUsizeError::ZeroValue));
     }
+
+    // "none" and a bare path must be recognized without touching the resolver
+    #[test]
+    fn parses_none_and_unix_syslog_targets() {
+        assert_eq!(parse_syslog_target("none").unwrap(), SyslogTarget::None);
+        assert_eq!(
+            parse_syslog_target("/dev/log").unwrap(),
+            SyslogTarget::Unix(PathBuf::from("/dev/log"))
+        );
+    }
+
+    // A literal IP must still work for both the `udp://` and `tcp://` schemes
+    #[test]
+    fn parses_literal_ip_syslog_targets() {
+        assert_eq!(
+            parse_syslog_target("udp://127.0.0.1:514").unwrap(),
+            SyslogTarget::Udp("127.0.0.1:514".parse().unwrap())
+        );
+        assert_eq!(
+            parse_syslog_target("tcp://127.0.0.1:601").unwrap(),
+            SyslogTarget::Tcp("127.0.0.1:601".parse().unwrap())
+        );
+    }
+
+    // A hostname must resolve instead of being rejected outright, since
+    // SocketAddr::parse only ever accepts literal IPs
+    #[test]
+    fn resolves_hostname_syslog_targets() {
+        assert!(parse_syslog_target("udp://localhost:514").is_ok());
+        assert!(parse_syslog_target("tcp://localhost:601").is_ok());
+    }
+
+    // A hostname that can't be resolved at all must still be a hard error
+    #[test]
+    fn parses_invalid_syslog_target() {
+        assert!(parse_syslog_target("udp://this.host.does.not.exist.invalid:514").is_err());
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!(parse_log_format("console"), Ok(LogFormat::Console));
+        assert_eq!(parse_log_format("json"), Ok(LogFormat::Json));
+        assert!(parse_log_format("xml").is_err());
+    }
 }
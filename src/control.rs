@@ -0,0 +1,228 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! A runtime control socket that lets an already-running instance be
+//! inspected and steered without restarting it, mirroring the
+//! controller-shell pattern of process supervisors that manage long-running
+//! workers over a control socket. `finshirctl` (see `src/bin/finshirctl.rs`)
+//! is the companion client.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::reload::ReloadableState;
+
+/// Binds `path` as a Unix domain socket and spawns a thread accepting
+/// line-based commands on it for as long as the process runs. Existing
+/// stale socket files are removed first, so a previous unclean shutdown
+/// doesn't keep the new instance from binding.
+pub fn install_control_socket(path: &Path, state: Arc<ReloadableState>) {
+    let _ = std::fs::remove_file(path);
+
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(
+                "Failed to bind the control socket at {:?} >>> {}! The control subsystem is disabled.",
+                path, err
+            );
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(stream) => {
+                    let state = Arc::clone(&state);
+                    std::thread::spawn(move || handle_connection(stream, &state));
+                }
+                Err(err) => error!("Failed to accept a control connection >>> {}!", err),
+            }
+        }
+    });
+
+    info!("The control socket has been bound at {:?}.", path);
+}
+
+// Every connection gets its own thread, and every line on it is handled as
+// one independent command -- long enough to support both one-shot
+// `finshirctl` invocations and an interactive `nc -U` session.
+fn handle_connection(stream: UnixStream, state: &ReloadableState) {
+    let mut writer = match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!("Failed to clone a control connection >>> {}!", err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+
+        let response = handle_command(line.trim(), state);
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_command(command: &str, state: &ReloadableState) -> String {
+    let mut parts = command.split_whitespace();
+
+    match parts.next() {
+        Some("stats") => {
+            let stats = state.stats();
+            format!(
+                "paused={} active_connections={} portions_sent={} bytes_sent={} send_failed={} reconnects={} \
+                 write_periodicity={} connecting={} sending={} blocked={} reconnecting={} failed={}",
+                stats.paused,
+                stats.active_connections,
+                stats.portions_sent,
+                stats.bytes_sent,
+                stats.send_failed,
+                stats.reconnects,
+                humantime::format_duration(stats.write_periodicity),
+                stats.connecting,
+                stats.sending,
+                stats.blocked,
+                stats.reconnecting,
+                stats.failed,
+            )
+        }
+        Some("pause") => {
+            state.set_paused(true);
+            "OK paused".to_owned()
+        }
+        Some("resume") => {
+            state.set_paused(false);
+            "OK resumed".to_owned()
+        }
+        Some("set-periodicity") => match parts.next().map(humantime::parse_duration) {
+            Some(Ok(duration)) => {
+                state.set_write_periodicity(duration);
+                format!("OK write_periodicity={}", humantime::format_duration(duration))
+            }
+            Some(Err(err)) => format!("ERR invalid duration >>> {}", err),
+            None => "ERR set-periodicity requires a TIME-SPAN argument".to_owned(),
+        },
+        Some(other) => format!("ERR unknown command {:?}", other),
+        None => "ERR empty command".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::reload::DynamicTesterConfig;
+
+    // A minimal on-disk portions file, just enough for `ReloadableState::load`
+    // to succeed -- these tests only care about `handle_command`'s behavior,
+    // not the portions it loads.
+    fn test_state() -> Arc<ReloadableState> {
+        let path = std::env::temp_dir().join(format!(
+            "finshir-control-test-{:?}.json",
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, r#"["abc"]"#).expect("Failed to write a temporary portions file");
+
+        ReloadableState::load(
+            path,
+            false,
+            DynamicTesterConfig {
+                write_periodicity: Duration::from_secs(30),
+                failed_count: NonZeroUsize::new(5).unwrap(),
+            },
+        )
+        .expect("Failed to load ReloadableState")
+    }
+
+    // Test that `stats` reports a freshly-loaded state's snapshot
+    #[test]
+    fn stats_reports_the_current_snapshot() {
+        let state = test_state();
+        let response = handle_command("stats", &state);
+
+        assert!(response.starts_with("paused=false"));
+        assert!(response.contains("active_connections=0"));
+    }
+
+    // Test that `pause`/`resume` toggle the paused flag and report accordingly
+    #[test]
+    fn pause_and_resume_toggle_the_paused_flag() {
+        let state = test_state();
+
+        assert_eq!(handle_command("pause", &state), "OK paused");
+        assert!(state.paused());
+
+        assert_eq!(handle_command("resume", &state), "OK resumed");
+        assert!(!state.paused());
+    }
+
+    // Test that `set-periodicity` with a valid TIME-SPAN updates the state
+    #[test]
+    fn set_periodicity_accepts_a_valid_duration() {
+        let state = test_state();
+
+        let response = handle_command("set-periodicity 1min", &state);
+        assert!(response.starts_with("OK write_periodicity="));
+        assert_eq!(state.stats().write_periodicity, Duration::from_secs(60));
+    }
+
+    // Test that an invalid TIME-SPAN is rejected without touching the state
+    #[test]
+    fn set_periodicity_rejects_an_invalid_duration() {
+        let state = test_state();
+
+        let response = handle_command("set-periodicity not-a-duration", &state);
+        assert!(response.starts_with("ERR"));
+        assert_eq!(state.stats().write_periodicity, Duration::from_secs(30));
+    }
+
+    // Test that `set-periodicity` with no argument is rejected
+    #[test]
+    fn set_periodicity_requires_an_argument() {
+        let state = test_state();
+        assert!(handle_command("set-periodicity", &state).starts_with("ERR"));
+    }
+
+    // Test that an unrecognized command is reported as such
+    #[test]
+    fn rejects_unknown_commands() {
+        let state = test_state();
+        assert_eq!(
+            handle_command("frobnicate", &state),
+            "ERR unknown command \"frobnicate\""
+        );
+    }
+
+    // Test that an empty command is reported as such
+    #[test]
+    fn rejects_empty_commands() {
+        let state = test_state();
+        assert_eq!(handle_command("", &state), "ERR empty command");
+    }
+}
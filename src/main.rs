@@ -27,8 +27,17 @@ use structopt::StructOpt;
 use crate::config::ArgsConfig;
 
 mod config;
+mod control;
+mod helpers;
+mod hooks;
+mod http2;
 mod logging;
+mod reload;
+mod reporting;
+mod template;
 mod testing;
+mod tls;
+mod tor;
 
 fn main() {
     setup_ctrlc_handler();
@@ -0,0 +1,396 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use serde_json;
+use signal_hook::iterator::Signals;
+
+use crate::helpers::{self, ReadPortionsError};
+use crate::template::{Template, TemplateError};
+
+/// The subset of `TesterConfig` that can be changed on the fly without
+/// tearing down already-established connections.
+#[derive(Debug, Clone)]
+pub struct DynamicTesterConfig {
+    pub write_periodicity: Duration,
+    pub failed_count: NonZeroUsize,
+}
+
+/// The data portions a coroutine sends, in whichever form `--template` asks
+/// for: literal bytes shared by every connection, or per-connection
+/// templates evaluated before every send.
+pub enum PortionSet {
+    Static(Vec<Vec<u8>>),
+    Templates(Vec<Template>),
+}
+
+impl PortionSet {
+    fn load(portions_file: &Path, template_mode: bool) -> Result<PortionSet, LoadPortionsError> {
+        if template_mode {
+            let file = File::open(portions_file).map_err(ReadPortionsError::ReadFailed)?;
+            let raw: Vec<String> =
+                serde_json::from_reader(file).map_err(ReadPortionsError::JsonParseFailed)?;
+
+            Ok(PortionSet::Templates(
+                raw.iter()
+                    .map(|source| Template::parse(source))
+                    .collect::<Result<_, _>>()?,
+            ))
+        } else {
+            Ok(PortionSet::Static(helpers::read_portions(portions_file)?))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PortionSet::Static(portions) => portions.len(),
+            PortionSet::Templates(templates) => templates.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadPortionsError {
+    Read(ReadPortionsError),
+    Template(TemplateError),
+}
+
+impl From<ReadPortionsError> for LoadPortionsError {
+    fn from(err: ReadPortionsError) -> LoadPortionsError {
+        LoadPortionsError::Read(err)
+    }
+}
+
+impl From<TemplateError> for LoadPortionsError {
+    fn from(err: TemplateError) -> LoadPortionsError {
+        LoadPortionsError::Template(err)
+    }
+}
+
+impl Display for LoadPortionsError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            LoadPortionsError::Read(err) => write!(fmt, "{}", err),
+            LoadPortionsError::Template(err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+impl Error for LoadPortionsError {}
+
+/// Everything a running test can change on the fly: the data portions, which
+/// are reloaded on SIGHUP, and the knobs above, whose `write_periodicity` is
+/// set through the control socket. Every coroutine grabs a fresh portions
+/// snapshot at the top of its reconnect loop and a fresh knobs snapshot
+/// before every send, so a change never disturbs a connection that's already
+/// pinned open.
+pub struct ReloadableState {
+    portions_file: PathBuf,
+    template_mode: bool,
+    portions: ArcSwap<PortionSet>,
+    dynamic: ArcSwap<DynamicTesterConfig>,
+
+    // Steered by the control socket (see `control.rs`) rather than SIGHUP.
+    paused: AtomicBool,
+
+    // Aggregated across every coroutine; read by both the `stats` control
+    // command and the periodic reporter (see `reporting.rs`).
+    portions_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    send_failed: AtomicU64,
+    reconnects: AtomicU64,
+    active_connections: AtomicUsize,
+
+    // How many coroutines currently sit in each `ConnectionState`.
+    connecting: AtomicUsize,
+    sending: AtomicUsize,
+    blocked: AtomicUsize,
+    reconnecting: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// The lifecycle state of a single coroutine's connection. `run_tester` and
+/// the HTTP/2 tester report every transition through `enter_state` and
+/// `leave_state`, so the statistics subsystem can show how many connections
+/// currently sit in each state.
+///
+/// `Blocked` is deliberately distinct from `Failed`: a `WouldBlock` write
+/// just means the socket isn't ready yet, so it yields the coroutine
+/// without spending one of `failed_count`'s genuine-failure retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Sending,
+    Blocked,
+    Reconnecting,
+    Failed,
+}
+
+/// A snapshot of the counters the `stats` control command dumps and the
+/// periodic reporter pushes onward.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub paused: bool,
+    pub portions_sent: u64,
+    pub bytes_sent: u64,
+    pub send_failed: u64,
+    pub reconnects: u64,
+    pub active_connections: usize,
+    pub write_periodicity: Duration,
+    pub connecting: usize,
+    pub sending: usize,
+    pub blocked: usize,
+    pub reconnecting: usize,
+    pub failed: usize,
+}
+
+impl ReloadableState {
+    // Performs the initial load of `portions_file` and wraps the result into
+    // a fresh `ReloadableState`, ready to be handed to `install_sighup_handler`.
+    pub fn load(
+        portions_file: PathBuf,
+        template_mode: bool,
+        dynamic: DynamicTesterConfig,
+    ) -> Result<Arc<ReloadableState>, LoadPortionsError> {
+        let portions = PortionSet::load(&portions_file, template_mode)?;
+
+        Ok(Arc::new(ReloadableState {
+            portions_file,
+            template_mode,
+            portions: ArcSwap::from_pointee(portions),
+            dynamic: ArcSwap::from_pointee(dynamic),
+            paused: AtomicBool::new(false),
+            portions_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            send_failed: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            connecting: AtomicUsize::new(0),
+            sending: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            reconnecting: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }))
+    }
+
+    pub fn portions(&self) -> Arc<PortionSet> {
+        self.portions.load_full()
+    }
+
+    pub fn dynamic(&self) -> Arc<DynamicTesterConfig> {
+        self.dynamic.load_full()
+    }
+
+    // Re-reads `portions_file` and publishes a new snapshot. On a parse
+    // error the previous, known-good snapshot is kept untouched, so a bad
+    // edit can never tear down the attack.
+    fn reload_portions(&self) {
+        match PortionSet::load(&self.portions_file, self.template_mode) {
+            Ok(portions) => {
+                info!(
+                    "Reloaded {} data portions from {:?}.",
+                    portions.len(),
+                    self.portions_file
+                );
+                self.portions.store(Arc::new(portions));
+            }
+            Err(err) => {
+                error!(
+                    "Failed to reload the portions file >>> {}! Keeping the previous data portions.",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Whether every coroutine should currently hold off on sending portions.
+    /// Checked in the `run` loop between `send_portion` calls, so a `pause`
+    /// takes effect on the very next iteration without tearing down any
+    /// already-open connection.
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Live-swaps `write_periodicity`, keeping `failed_count` untouched.
+    /// Coroutines pick this up on their next reconnect or, for an
+    /// already-open connection, at the next `coroutine::sleep`.
+    pub fn set_write_periodicity(&self, write_periodicity: Duration) {
+        let current = self.dynamic();
+        self.dynamic.store(Arc::new(DynamicTesterConfig {
+            write_periodicity,
+            failed_count: current.failed_count,
+        }));
+    }
+
+    pub fn record_sent(&self, bytes: u64) {
+        self.portions_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_send_failed(&self) {
+        self.send_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_opened(&self) {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn state_counter(&self, state: ConnectionState) -> &AtomicUsize {
+        match state {
+            ConnectionState::Connecting => &self.connecting,
+            ConnectionState::Sending => &self.sending,
+            ConnectionState::Blocked => &self.blocked,
+            ConnectionState::Reconnecting => &self.reconnecting,
+            ConnectionState::Failed => &self.failed,
+        }
+    }
+
+    /// Marks one coroutine as having entered `state`. Pair with a matching
+    /// `leave_state` once it transitions elsewhere.
+    pub fn enter_state(&self, state: ConnectionState) {
+        self.state_counter(state).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn leave_state(&self, state: ConnectionState) {
+        self.state_counter(state).fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            paused: self.paused(),
+            portions_sent: self.portions_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            send_failed: self.send_failed.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            write_periodicity: self.dynamic().write_periodicity,
+            connecting: self.connecting.load(Ordering::Relaxed),
+            sending: self.sending.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            reconnecting: self.reconnecting.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Installs a SIGHUP handler that reloads `state.portions_file` into `state`
+/// every time the signal arrives, without interrupting any coroutine that's
+/// already running.
+pub fn install_sighup_handler(state: Arc<ReloadableState>) {
+    let signals =
+        Signals::new(&[signal_hook::SIGHUP]).expect("Error while setting the SIGHUP handler");
+
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("SIGHUP received. Reloading the portions file...");
+            state.reload_portions();
+        }
+    });
+
+    trace!("The SIGHUP handler has been configured.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal on-disk portions file, just enough for `ReloadableState::load`
+    // to succeed -- the state-transition tests below don't care about its
+    // contents, only that loading one works.
+    fn load_test_state() -> Arc<ReloadableState> {
+        let path = std::env::temp_dir().join(format!("finshir-reload-test-{:?}.json", std::thread::current().id()));
+        std::fs::write(&path, r#"["abc"]"#).expect("Failed to write a temporary portions file");
+
+        let state = ReloadableState::load(
+            path,
+            false,
+            DynamicTesterConfig {
+                write_periodicity: Duration::from_millis(1),
+                failed_count: NonZeroUsize::new(1).unwrap(),
+            },
+        )
+        .expect("Failed to load ReloadableState");
+
+        state
+    }
+
+    // Entering and leaving the same state must bring its counter back to
+    // zero, and must never disturb an unrelated state's counter
+    #[test]
+    fn enter_leave_state_balance() {
+        let state = load_test_state();
+
+        state.enter_state(ConnectionState::Connecting);
+        assert_eq!(state.stats().connecting, 1);
+        assert_eq!(state.stats().sending, 0);
+
+        state.enter_state(ConnectionState::Sending);
+        assert_eq!(state.stats().sending, 1);
+
+        state.leave_state(ConnectionState::Connecting);
+        assert_eq!(state.stats().connecting, 0);
+        assert_eq!(state.stats().sending, 1);
+
+        state.leave_state(ConnectionState::Sending);
+        assert_eq!(state.stats().sending, 0);
+    }
+
+    // Every `ConnectionState` variant tracks its own independent counter
+    #[test]
+    fn every_state_tracks_independently() {
+        let state = load_test_state();
+
+        for variant in [
+            ConnectionState::Connecting,
+            ConnectionState::Sending,
+            ConnectionState::Blocked,
+            ConnectionState::Reconnecting,
+            ConnectionState::Failed,
+        ] {
+            state.enter_state(variant);
+        }
+
+        let stats = state.stats();
+        assert_eq!(stats.connecting, 1);
+        assert_eq!(stats.sending, 1);
+        assert_eq!(stats.blocked, 1);
+        assert_eq!(stats.reconnecting, 1);
+        assert_eq!(stats.failed, 1);
+    }
+}
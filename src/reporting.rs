@@ -0,0 +1,180 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! Periodically summarizes the live statistics aggregated in
+//! `ReloadableState` and, optionally, pushes them onward to an external
+//! collector -- the same role a tracer/reporter plays when it periodically
+//! flushes spans to a backend.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use time;
+
+use crate::config::ReportingConfig;
+use crate::helpers;
+use crate::reload::ReloadableState;
+
+// How long `push_report` waits to dial the collector and to read back its
+// response before giving up. An unresponsive --report-endpoint must never be
+// able to stall this thread -- it also emits the periodic "Live stats" log
+// line, so a hung collector would silently kill local stats logging too.
+const REPORT_DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+const REPORT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The small structured record pushed to `--report-endpoint`.
+#[derive(Serialize)]
+struct Report {
+    timestamp: String,
+    target: SocketAddr,
+    active_connections: usize,
+    bytes_total: u64,
+    failures_total: u64,
+}
+
+/// Spawns a background thread that wakes up every `report_interval`, logs a
+/// summary line, and (if `report_endpoint` is set) pushes a JSON record to
+/// it over plain HTTP. The hot `run` loop never touches this thread directly
+/// -- it only ever bumps the lock-free atomics inside `state`.
+pub fn install_reporter(config: &ReportingConfig, state: Arc<ReloadableState>, target: SocketAddr) {
+    let interval = config.report_interval;
+    let endpoint = config.report_endpoint.clone();
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+
+        let stats = state.stats();
+        info!(
+            "Live stats >>> {} active connections ({} connecting, {} sending, {} blocked, {} \
+             reconnecting, {} failed), {} bytes sent, {} successes, {} failures, {} reconnects.",
+            helpers::cyan(stats.active_connections),
+            helpers::cyan(stats.connecting),
+            helpers::cyan(stats.sending),
+            helpers::cyan(stats.blocked),
+            helpers::cyan(stats.reconnecting),
+            helpers::cyan(stats.failed),
+            helpers::cyan(stats.bytes_sent),
+            helpers::cyan(stats.portions_sent),
+            helpers::cyan(stats.send_failed),
+            helpers::cyan(stats.reconnects),
+        );
+
+        if let Some(endpoint) = &endpoint {
+            let report = Report {
+                timestamp: time::strftime("%Y-%m-%dT%H:%M:%S%z", &time::now()).unwrap(),
+                target,
+                active_connections: stats.active_connections,
+                bytes_total: stats.bytes_sent,
+                failures_total: stats.send_failed,
+            };
+
+            if let Err(err) = push_report(endpoint, &report) {
+                error!("Failed to push the stats report to {} >>> {}!", endpoint, err);
+            }
+        }
+    });
+}
+
+// Posts `report` as a JSON body to `endpoint` over a plain, one-shot
+// HTTP/1.1 connection -- the rest of the codebase doesn't depend on a full
+// HTTP client crate, so a hand-rolled request is more consistent than
+// pulling one in just for this.
+fn push_report(endpoint: &str, report: &Report) -> io::Result<()> {
+    let (authority, path) = split_endpoint(endpoint)?;
+
+    let body = serde_json::to_vec(report)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    let address = authority
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "could not resolve the report endpoint"))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, REPORT_DIAL_TIMEOUT)?;
+    stream.set_read_timeout(Some(REPORT_READ_TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {length}\r\n\
+         Connection: close\r\n\r\n",
+        path = path,
+        host = authority,
+        length = body.len(),
+    )?;
+    stream.write_all(&body)?;
+
+    // Drain and discard the response -- we only care that the write made it
+    // onto the wire, not about the collector's reply. A collector that never
+    // closes the connection can't stall us past REPORT_READ_TIMEOUT.
+    let mut discard = [0u8; 512];
+    while let Ok(n) = stream.read(&mut discard) {
+        if n == 0 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Splits an `http://` endpoint into its authority (`host:port`) and request
+// path, defaulting to `/` when none is given. Pulled out of `push_report` so
+// this parsing can be exercised without any socket I/O.
+fn split_endpoint(endpoint: &str) -> io::Result<(&str, &str)> {
+    let without_scheme = endpoint
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "only http:// endpoints are supported"))?;
+
+    Ok(match without_scheme.find('/') {
+        Some(index) => (&without_scheme[..index], &without_scheme[index..]),
+        None => (without_scheme, "/"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test that an endpoint with an explicit path splits into authority and path
+    #[test]
+    fn splits_authority_and_path() {
+        let (authority, path) = split_endpoint("http://127.0.0.1:8080/reports").unwrap();
+        assert_eq!(authority, "127.0.0.1:8080");
+        assert_eq!(path, "/reports");
+    }
+
+    // Test that an endpoint with no path defaults to "/"
+    #[test]
+    fn defaults_to_root_path() {
+        let (authority, path) = split_endpoint("http://127.0.0.1:8080").unwrap();
+        assert_eq!(authority, "127.0.0.1:8080");
+        assert_eq!(path, "/");
+    }
+
+    // Test that a non-http scheme is rejected
+    #[test]
+    fn rejects_non_http_schemes() {
+        assert!(split_endpoint("https://127.0.0.1:8080").is_err());
+        assert!(split_endpoint("127.0.0.1:8080").is_err());
+    }
+}
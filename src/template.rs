@@ -0,0 +1,499 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! A tiny expression language for per-connection, per-send payload
+//! generation. A portion string may embed `{...}` placeholders, evaluated
+//! fresh for every coroutine and every send, so `--connections 1000` no
+//! longer produces 1000 byte-identical streams.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::Rng;
+use uuid::Uuid;
+
+/// A portion string parsed once at startup into literal and placeholder
+/// nodes. Evaluating it is cheap enough to do before every `send_portion`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(String),
+    Var(String),
+    Call(String, Vec<Node>),
+}
+
+/// Per-coroutine state exposed to templates through `Var` and the `counter`
+/// function.
+pub struct Environment {
+    conn_id: u64,
+    counter: AtomicU64,
+}
+
+impl Environment {
+    pub fn new(conn_id: u64) -> Environment {
+        Environment {
+            conn_id,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    fn next_counter(&self) -> u64 {
+        self.counter.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Template {
+    /// Parses `source` into an AST. Malformed placeholders are caught here;
+    /// unknown functions, undefined variables, wrong argument counts, and
+    /// arguments of the wrong shape (e.g. a non-numeric bound) are only
+    /// reported by `eval`.
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                // `{{` and `}}` are the escapes for literal braces.
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    if !literal.is_empty() {
+                        nodes.push(Node::Literal(std::mem::take(&mut literal)));
+                    }
+                    nodes.push(parse_placeholder(&mut chars)?);
+                }
+                '}' => return Err(TemplateError::UnmatchedClosingBrace),
+                other => literal.push(other),
+            }
+        }
+
+        if !literal.is_empty() {
+            nodes.push(Node::Literal(literal));
+        }
+
+        Ok(Template { nodes })
+    }
+
+    /// Walks the AST, resolving every `Var` and `Call` against `env`, and
+    /// concatenates the result into a single `String`.
+    pub fn eval(&self, env: &Environment) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        for node in &self.nodes {
+            out.push_str(&eval_node(node, env)?);
+        }
+        Ok(out)
+    }
+}
+
+fn parse_placeholder(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<Node, TemplateError> {
+    let mut depth = 0usize;
+    let mut expr_src = String::new();
+
+    loop {
+        match chars.next() {
+            Some('}') if depth == 0 => break,
+            Some(c) => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                expr_src.push(c);
+            }
+            None => return Err(TemplateError::UnterminatedPlaceholder),
+        }
+    }
+
+    let tokens = tokenize(&expr_src)?;
+    let mut parser = ExprParser {
+        tokens: &tokens,
+        pos: 0,
+    };
+    let node = parser.parse_expr()?;
+    parser.expect_end()?;
+    Ok(node)
+}
+
+fn eval_node(node: &Node, env: &Environment) -> Result<String, TemplateError> {
+    match node {
+        Node::Literal(value) => Ok(value.clone()),
+        Node::Var(name) => match name.as_str() {
+            "conn_id" => Ok(env.conn_id.to_string()),
+            other => Err(TemplateError::UndefinedVariable(other.to_string())),
+        },
+        Node::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval_node(arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            call_function(name, &args, env)
+        }
+    }
+}
+
+fn call_function(name: &str, args: &[String], env: &Environment) -> Result<String, TemplateError> {
+    match name {
+        "random_int" => {
+            expect_arity(name, args, 2)?;
+            let (lo, hi) = (expect_int(name, args, 0)?, expect_int(name, args, 1)?);
+            if lo >= hi {
+                return Err(TemplateError::InvalidArgument(format!(
+                    "random_int({}, {})",
+                    lo, hi
+                )));
+            }
+            Ok(rand::thread_rng().gen_range(lo, hi).to_string())
+        }
+        "random_hex" => {
+            expect_arity(name, args, 1)?;
+            let len = expect_int(name, args, 0)?;
+            if len < 0 {
+                return Err(TemplateError::InvalidArgument(format!("random_hex({})", len)));
+            }
+            let len = len as usize;
+            let mut rng = rand::thread_rng();
+            Ok((0..len).map(|_| format!("{:x}", rng.gen_range(0, 16))).collect())
+        }
+        "uuid" => {
+            expect_arity(name, args, 0)?;
+            Ok(Uuid::new_v4().to_string())
+        }
+        "counter" => {
+            expect_arity(name, args, 0)?;
+            Ok(env.next_counter().to_string())
+        }
+        "now" => {
+            expect_arity(name, args, 1)?;
+            time::strftime(&args[0], &time::now())
+                .map_err(|_| TemplateError::InvalidArgument(args[0].clone()))
+        }
+        other => Err(TemplateError::UnknownFunction(other.to_string())),
+    }
+}
+
+fn expect_arity(name: &str, args: &[String], expected: usize) -> Result<(), TemplateError> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(TemplateError::ArityMismatch {
+            function: name.to_string(),
+            expected,
+            got: args.len(),
+        })
+    }
+}
+
+fn expect_int(name: &str, args: &[String], index: usize) -> Result<i64, TemplateError> {
+    args.get(index)
+        .ok_or_else(|| TemplateError::ArityMismatch {
+            function: name.to_string(),
+            expected: index + 1,
+            got: args.len(),
+        })?
+        .parse()
+        .map_err(|_| TemplateError::InvalidArgument(args[index].clone()))
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(TemplateError::UnterminatedString),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut value = String::new();
+                value.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() {
+                        value.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(value));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut value = String::new();
+                value.push(c);
+                chars.next();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        value.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(value));
+            }
+            other => return Err(TemplateError::UnexpectedCharacter(other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn next(&mut self) -> Result<&'a Token, TemplateError> {
+        let token = self.tokens.get(self.pos).ok_or(TemplateError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect_end(&self) -> Result<(), TemplateError> {
+        if self.pos == self.tokens.len() {
+            Ok(())
+        } else {
+            Err(TemplateError::TrailingTokens)
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Node, TemplateError> {
+        match self.next()?.clone() {
+            Token::Number(value) => Ok(Node::Literal(value)),
+            Token::Str(value) => Ok(Node::Literal(value)),
+            Token::Ident(name) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.next()?;
+                    let mut args = Vec::new();
+
+                    if self.peek() != Some(&Token::RParen) {
+                        loop {
+                            args.push(self.parse_expr()?);
+                            match self.next()?.clone() {
+                                Token::Comma => continue,
+                                Token::RParen => break,
+                                _ => return Err(TemplateError::ExpectedCommaOrCloseParen),
+                            }
+                        }
+                    } else {
+                        self.next()?;
+                    }
+
+                    Ok(Node::Call(name, args))
+                } else {
+                    Ok(Node::Var(name))
+                }
+            }
+            _ => Err(TemplateError::UnexpectedToken),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    UnterminatedPlaceholder,
+    UnmatchedClosingBrace,
+    UnterminatedString,
+    UnexpectedCharacter(char),
+    UnexpectedToken,
+    UnexpectedEnd,
+    TrailingTokens,
+    ExpectedCommaOrCloseParen,
+    UnknownFunction(String),
+    UndefinedVariable(String),
+    InvalidArgument(String),
+    ArityMismatch {
+        function: String,
+        expected: usize,
+        got: usize,
+    },
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            TemplateError::UnterminatedPlaceholder => {
+                write!(fmt, "a `{{` placeholder is never closed")
+            }
+            TemplateError::UnmatchedClosingBrace => write!(fmt, "a stray `}}` was found"),
+            TemplateError::UnterminatedString => write!(fmt, "a string literal is never closed"),
+            TemplateError::UnexpectedCharacter(c) => write!(fmt, "unexpected character '{}'", c),
+            TemplateError::UnexpectedToken => write!(fmt, "unexpected token in an expression"),
+            TemplateError::UnexpectedEnd => write!(fmt, "an expression ends unexpectedly"),
+            TemplateError::TrailingTokens => write!(fmt, "trailing tokens after an expression"),
+            TemplateError::ExpectedCommaOrCloseParen => {
+                write!(fmt, "expected ',' or ')' in a function call")
+            }
+            TemplateError::UnknownFunction(name) => write!(fmt, "unknown function '{}'", name),
+            TemplateError::UndefinedVariable(name) => write!(fmt, "undefined variable '{}'", name),
+            TemplateError::InvalidArgument(arg) => write!(fmt, "invalid argument '{}'", arg),
+            TemplateError::ArityMismatch {
+                function,
+                expected,
+                got,
+            } => write!(
+                fmt,
+                "'{}' expects {} argument(s), got {}",
+                function, expected, got
+            ),
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Literal text and the `conn_id` variable must both come through intact
+    #[test]
+    fn evaluates_literals_and_conn_id() {
+        let template = Template::parse("id={conn_id} literal").unwrap();
+        let env = Environment::new(42);
+
+        assert_eq!(template.eval(&env).unwrap(), "id=42 literal");
+    }
+
+    // Escaped braces must produce literal `{` and `}` instead of starting a
+    // placeholder
+    #[test]
+    fn escaped_braces_are_literal() {
+        let template = Template::parse("{{literal}}").unwrap();
+        let env = Environment::new(0);
+
+        assert_eq!(template.eval(&env).unwrap(), "{literal}");
+    }
+
+    // `counter()` must be monotonic within a single `Environment`
+    #[test]
+    fn counter_is_monotonic_per_environment() {
+        let template = Template::parse("{counter()}").unwrap();
+        let env = Environment::new(0);
+
+        assert_eq!(template.eval(&env).unwrap(), "0");
+        assert_eq!(template.eval(&env).unwrap(), "1");
+        assert_eq!(template.eval(&env).unwrap(), "2");
+    }
+
+    // Nested calls must be supported, e.g. passing a call as an argument to
+    // another call
+    #[test]
+    fn nested_calls_are_supported() {
+        let template = Template::parse("{random_int(counter(), 100)}").unwrap();
+        let env = Environment::new(0);
+
+        let result: i64 = template.eval(&env).unwrap().parse().unwrap();
+        assert!(result >= 0 && result < 100);
+    }
+
+    // An undefined variable must be a hard evaluation error, never a silent
+    // empty string
+    #[test]
+    fn undefined_variable_is_an_error() {
+        let template = Template::parse("{nonexistent}").unwrap();
+        let env = Environment::new(0);
+
+        assert!(template.eval(&env).is_err());
+    }
+
+    // Unterminated placeholders must be rejected at parse time
+    #[test]
+    fn unterminated_placeholder_is_a_parse_error() {
+        assert!(Template::parse("hello {conn_id").is_err());
+    }
+
+    // `random_int`'s bounds must be validated at evaluation time instead of
+    // panicking the coroutine
+    #[test]
+    fn random_int_rejects_empty_or_inverted_range() {
+        let env = Environment::new(0);
+
+        assert!(Template::parse("{random_int(5, 5)}")
+            .unwrap()
+            .eval(&env)
+            .is_err());
+        assert!(Template::parse("{random_int(5, 1)}")
+            .unwrap()
+            .eval(&env)
+            .is_err());
+    }
+
+    // A negative `random_hex` length must be a hard evaluation error, never
+    // a silent wraparound into a huge allocation
+    #[test]
+    fn random_hex_rejects_negative_length() {
+        let template = Template::parse("{random_hex(-1)}").unwrap();
+        let env = Environment::new(0);
+
+        assert!(template.eval(&env).is_err());
+    }
+}
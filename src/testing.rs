@@ -16,30 +16,77 @@
 //
 // For more information see <https://github.com/Gymmasssorla/finshir>.
 
+use std::borrow::Cow;
 use std::io::{self, Write};
 use std::num::NonZeroUsize;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::sync::Arc;
 
+use futures::future;
 use humantime::format_duration;
 use may::{self, coroutine, go};
-use tor_stream::TorStream;
 
 use crate::config::{ArgsConfig, SocketConfig, TesterConfig};
+use crate::control;
 use crate::helpers;
+use crate::hooks::{self, Event};
+use crate::reload::{self, ConnectionState, DynamicTesterConfig, PortionSet, ReloadableState};
+use crate::reporting;
+use crate::template::Environment;
+use crate::tls::Socket;
+use crate::tor::TorContext;
 use std::time::Instant;
 
 type StdSocket = std::net::TcpStream;
 type MaySocket = may::net::TcpStream;
 
+// How often a paused coroutine checks back in on the control socket's
+// `paused` flag while it's idling.
+const PAUSE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+// The backoff `connect_socket` waits between reconnect attempts, doubling on
+// every consecutive failure up to `RECONNECT_BACKOFF_MAX`.
+const RECONNECT_BACKOFF_INITIAL: std::time::Duration = std::time::Duration::from_millis(500);
+const RECONNECT_BACKOFF_MAX: std::time::Duration = std::time::Duration::from_secs(30);
+
 pub fn run(config: &ArgsConfig) -> i32 {
-    let portions = match helpers::read_portions(&config.portions_file) {
+    let state = match ReloadableState::load(
+        config.portions_file.clone(),
+        config.tester_config.template_mode,
+        DynamicTesterConfig {
+            write_periodicity: config.tester_config.write_periodicity,
+            failed_count: config.tester_config.failed_count,
+        },
+    ) {
         Err(err) => {
             error!("Failed to parse the JSON >>> {}!", err);
             return 1;
         }
-        Ok(res) => res,
+        Ok(state) => state,
+    };
+    reload::install_sighup_handler(Arc::clone(&state));
+
+    if let Some(control_socket) = &config.control_socket {
+        control::install_control_socket(control_socket, Arc::clone(&state));
+    }
+
+    reporting::install_reporter(
+        &config.reporting_config,
+        Arc::clone(&state),
+        config.tester_config.socket_config.receiver,
+    );
+
+    let tor_context = if config.tester_config.socket_config.use_tor {
+        match TorContext::bootstrap(&config.tester_config.socket_config.tor_bridges) {
+            Ok(context) => Some(Arc::new(context)),
+            Err(err) => {
+                error!("Failed to bootstrap the Tor client >>> {}!", err);
+                return 1;
+            }
+        }
+    } else {
+        None
     };
-    let portions: Vec<&[u8]> = portions.iter().map(Vec::as_slice).collect();
 
     warn!(
         "Waiting {} and then spawning {} coroutines connected through the {}.",
@@ -53,121 +100,329 @@ pub fn run(config: &ArgsConfig) -> i32 {
     );
     std::thread::sleep(config.wait);
 
+    // Every --http2 connection is a Tokio task, not a `may` coroutine, so
+    // they all share this one runtime rather than each spinning up its own
+    // -- with the default --connections, one runtime driving many tasks is
+    // the whole point of staying cheap, the same reason the byte-stream
+    // tester leans on `may` instead of an OS thread per connection.
+    let http2_runtime = if config.tester_config.http2_config.http2 {
+        Some(Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the shared Tokio runtime for the HTTP/2 tester"),
+        ))
+    } else {
+        None
+    };
+
     coroutine::scope(|scope| {
-        let portions = &portions;
         let config = &config;
         let iters = config.connections.get();
 
-        for _ in 0..iters {
-            go!(scope, move || run_tester(&config.tester_config, portions));
+        for conn_id in 0..iters {
+            let state = Arc::clone(&state);
+            let tor_context = tor_context.clone();
+
+            if config.tester_config.http2_config.http2 {
+                // Has no data portions for SIGHUP to reload, but shares the
+                // same ReloadableState and hooks_config as the byte-stream
+                // path, so stats/the control socket/--hook-on-* all still
+                // see it.
+                let runtime = Arc::clone(http2_runtime.as_ref().unwrap());
+                runtime.spawn(crate::http2::run(
+                    config.tester_config.socket_config.clone(),
+                    config.tester_config.http2_config.clone(),
+                    config.tester_config.hooks_config.clone(),
+                    config.tester_config.write_periodicity,
+                    state,
+                    conn_id as u64,
+                ));
+                continue;
+            }
+
+            go!(scope, move || run_tester(
+                &config.tester_config,
+                &state,
+                conn_id as u64,
+                tor_context
+            ));
         }
 
         info!("All the coroutines have been spawned.");
     });
 
+    // With --http2 no byte-stream coroutine is spawned, so `coroutine::scope`
+    // returns right away -- nothing else is keeping this thread alive then,
+    // so block it on the shared runtime instead of falling through and
+    // exiting.
+    if let Some(runtime) = http2_runtime {
+        runtime.block_on(future::pending::<()>());
+    }
+
     return 0;
 }
 
-fn run_tester(config: &TesterConfig, portions: &[&[u8]]) {
-    let fmt_per = helpers::cyan(format_duration(config.write_periodicity));
+fn run_tester(
+    config: &TesterConfig,
+    state: &Arc<ReloadableState>,
+    conn_id: u64,
+    tor_context: Option<Arc<TorContext>>,
+) {
     let start = Instant::now();
+    let env = Environment::new(conn_id);
+    let mut is_reconnect = false;
+    let mut bytes_sent: u64 = 0;
+
+    // `connect_socket` expects the coroutine to already be marked
+    // `Connecting` when it's called, and leaves it there on success --
+    // `Sending` is entered right after a socket comes back.
+    state.enter_state(ConnectionState::Connecting);
 
     loop {
-        let mut socket: MaySocket = connect_socket(&config.socket_config);
+        // Grab a fresh snapshot of the hot-reloadable portions at the top of
+        // the reconnect loop, so a SIGHUP mid-test never tears down a
+        // connection that's already pinned open.
+        let portions = state.portions();
+
+        let mut socket: Socket = connect_socket(&config.socket_config, tor_context.as_deref(), state);
+        state.leave_state(ConnectionState::Connecting);
+        state.enter_state(ConnectionState::Sending);
 
-        for &portion in portions {
+        state.connection_opened();
+        if is_reconnect {
+            state.record_reconnect();
+        }
+        hooks::fire(
+            if is_reconnect {
+                Event::Reconnect
+            } else {
+                Event::Connect
+            },
+            &config.hooks_config,
+            config.socket_config.receiver,
+            conn_id,
+            bytes_sent,
+        );
+        is_reconnect = true;
+
+        // Whether this round ended because `send_portion` gave up on a
+        // portion (current state `Failed`) rather than exhausting the list
+        // normally (current state `Sending`) -- decides which one to leave
+        // before looping back around to reconnect.
+        let mut round_failed = false;
+
+        for index in 0..portions.len() {
             if start.elapsed() >= config.test_duration {
                 info!("The allotted time has passed. The coroutine has exited.");
+                state.leave_state(ConnectionState::Sending);
+                state.connection_closed();
+                hooks::fire(
+                    Event::Exit,
+                    &config.hooks_config,
+                    config.socket_config.receiver,
+                    conn_id,
+                    bytes_sent,
+                );
                 return;
             }
 
-            match send_portion(&mut socket, portion, config.failed_count) {
-                SendPortionResult::Success => {
+            // Checked between every send, so `pause`/`set-periodicity`
+            // issued over the control socket take effect on the very next
+            // iteration instead of waiting for a reconnect.
+            while state.paused() {
+                coroutine::sleep(PAUSE_POLL_INTERVAL);
+            }
+            let dynamic = state.dynamic();
+            let fmt_per = helpers::cyan(format_duration(dynamic.write_periodicity));
+
+            let portion: Cow<[u8]> = match &*portions {
+                PortionSet::Static(list) => Cow::Borrowed(list[index].as_slice()),
+                PortionSet::Templates(templates) => match templates[index].eval(&env) {
+                    Ok(rendered) => Cow::Owned(rendered.into_bytes()),
+                    Err(err) => {
+                        error!(
+                            "Failed to evaluate a portion template >>> {}! Skipping it...",
+                            err
+                        );
+                        continue;
+                    }
+                },
+            };
+
+            match send_portion(
+                &mut socket,
+                &portion,
+                dynamic.failed_count,
+                state,
+                &config.socket_config,
+                index,
+            ) {
+                SendPortionResult::Success { attempts } => {
+                    bytes_sent += portion.len() as u64;
+                    state.record_sent(portion.len() as u64);
                     info!(
+                        target_addr = config.socket_config.receiver.to_string(),
+                        portion_index = index as u64,
+                        bytes = portion.len() as u64,
+                        retry_attempt = attempts,
+                        tor = config.socket_config.use_tor;
                         "{} bytes have been sent. Waiting {}...",
                         helpers::cyan(portion.len()),
                         fmt_per
                     );
                 }
-                SendPortionResult::Failed(err) => {
+                SendPortionResult::Failed { err, attempts } => {
+                    state.record_send_failed();
                     error!(
+                        target_addr = config.socket_config.receiver.to_string(),
+                        portion_index = index as u64,
+                        bytes = portion.len() as u64,
+                        retry_attempt = attempts,
+                        tor = config.socket_config.use_tor;
                         "Sending {} bytes failed {} times >>> {}! Reconnecting the socket...",
                         helpers::cyan(portion.len()),
-                        helpers::cyan(config.failed_count),
+                        helpers::cyan(dynamic.failed_count),
                         err,
                     );
+                    round_failed = true;
                     break;
                 }
             }
 
-            coroutine::sleep(config.write_periodicity);
+            coroutine::sleep(dynamic.write_periodicity);
         }
 
+        state.leave_state(if round_failed {
+            ConnectionState::Failed
+        } else {
+            ConnectionState::Sending
+        });
+        state.enter_state(ConnectionState::Connecting);
+        state.connection_closed();
         info!("All the data portions have been sent. Reconnecting the socket...");
     }
 }
 
 #[derive(Debug)]
 enum SendPortionResult {
-    Success,
-    Failed(io::Error),
+    Success { attempts: u64 },
+    Failed { err: io::Error, attempts: u64 },
 }
 
+// `WouldBlock` is treated separately from a genuine write failure: it just
+// means the socket isn't ready yet, so it yields the coroutine through the
+// `Blocked` state instead of spending one of `failed_count`'s retries.
 fn send_portion(
-    socket: &mut MaySocket,
+    socket: &mut Socket,
     portion: &[u8],
     failed_count: NonZeroUsize,
+    state: &ReloadableState,
+    socket_config: &SocketConfig,
+    portion_index: usize,
 ) -> SendPortionResult {
-    let res = {
-        for _ in 0..(failed_count.get() - 1) {
-            match socket.write_all(portion) {
-                Ok(_) => return SendPortionResult::Success,
-                Err(err) => {
-                    error!(
-                        "Failed to send {} bytes >>> {}! Retrying the operation...",
-                        helpers::cyan(portion.len()),
-                        err
-                    );
-                    continue;
+    let mut attempt = 0u64;
+
+    let write_result = loop {
+        match socket.write_all(portion) {
+            Ok(_) => break Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                state.leave_state(ConnectionState::Sending);
+                state.enter_state(ConnectionState::Blocked);
+                coroutine::yield_now();
+                state.leave_state(ConnectionState::Blocked);
+                state.enter_state(ConnectionState::Sending);
+                continue;
+            }
+            Err(err) => {
+                attempt += 1;
+                if attempt >= failed_count.get() as u64 {
+                    break Err(err);
                 }
+
+                error!(
+                    target_addr = socket_config.receiver.to_string(),
+                    portion_index = portion_index as u64,
+                    bytes = portion.len() as u64,
+                    retry_attempt = attempt,
+                    tor = socket_config.use_tor;
+                    "Failed to send {} bytes >>> {}! Retrying the operation...",
+                    helpers::cyan(portion.len()),
+                    err
+                );
             }
         }
+    };
 
-        match socket.write_all(portion) {
-            Ok(_) => SendPortionResult::Success,
-            Err(err) => SendPortionResult::Failed(err),
-        }
+    let result = match write_result {
+        Ok(_) => SendPortionResult::Success { attempts: attempt },
+        Err(err) => SendPortionResult::Failed { err, attempts: attempt },
     };
+    let result = socket.flush().map_or_else(
+        |err| SendPortionResult::Failed { err, attempts: attempt },
+        |_| result,
+    );
 
-    socket
-        .flush()
-        .map_or_else(SendPortionResult::Failed, |_| res)
+    if let SendPortionResult::Failed { .. } = &result {
+        state.leave_state(ConnectionState::Sending);
+        state.enter_state(ConnectionState::Failed);
+    }
+
+    result
 }
 
-fn connect_socket(config: &SocketConfig) -> MaySocket {
+// Expects the caller to have already entered `ConnectionState::Connecting`
+// and leaves the coroutine in that same state on return -- the caller
+// transitions onward to `Sending` once it has the socket in hand.
+fn connect_socket(
+    config: &SocketConfig,
+    tor_context: Option<&TorContext>,
+    state: &ReloadableState,
+) -> Socket {
+    let mut backoff = RECONNECT_BACKOFF_INITIAL;
+
     loop {
-        match try_connect_socket(config) {
+        match try_connect_socket(config, tor_context) {
             Ok(socket) => {
                 info!("A new socket has been connected.");
                 return socket;
             }
             Err(err) => {
                 error!(
-                    "Failed to connect a socket >>> {}! Retrying the operation...",
-                    err
+                    "Failed to connect a socket >>> {}! Retrying in {}...",
+                    err,
+                    format_duration(backoff)
                 );
-                continue;
+
+                state.leave_state(ConnectionState::Connecting);
+                state.enter_state(ConnectionState::Reconnecting);
+                coroutine::sleep(backoff);
+                state.leave_state(ConnectionState::Reconnecting);
+                state.enter_state(ConnectionState::Connecting);
+
+                backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
             }
         }
     }
 }
 
-fn try_connect_socket(config: &SocketConfig) -> io::Result<MaySocket> {
-    let socket = if config.use_tor {
-        TorStream::connect(config.receiver)?.unwrap()
-    } else {
-        StdSocket::connect_timeout(&config.receiver, config.connect_timeout)?
-    };
+fn try_connect_socket(config: &SocketConfig, tor_context: Option<&TorContext>) -> io::Result<Socket> {
+    if config.use_tor {
+        let tor_context = tor_context
+            .expect("`--use-tor` was passed, but the Tor client was never bootstrapped");
+        let stream = tor_context.connect(
+            config.receiver,
+            config.connect_timeout,
+            config.write_timeout,
+            config.tor_isolate_circuits,
+        )?;
+
+        // Slow partial-record writes through the TLS layer keep the connection
+        // pinned open exactly like slow raw writes do for plaintext sockets.
+        return Socket::wrap(stream, config);
+    }
+
+    let socket = StdSocket::connect_timeout(&config.receiver, config.connect_timeout)?;
 
     // We send packets quite rarely (the default is 30secs), so the Nagle algorithm
     // doesn't help us
@@ -181,5 +436,6 @@ fn try_connect_socket(config: &SocketConfig) -> io::Result<MaySocket> {
         socket.set_ttl(val)?;
     }
 
-    unsafe { Ok(MaySocket::from_raw_fd(socket.into_raw_fd())) }
+    let socket = unsafe { MaySocket::from_raw_fd(socket.into_raw_fd()) };
+    Socket::wrap(socket, config)
 }
@@ -0,0 +1,124 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use rustls::{
+    Certificate, ClientConfig, ClientSession, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, TLSError,
+};
+use webpki::DNSNameRef;
+
+use crate::config::SocketConfig;
+
+// Any transport the tester might connect over -- plain TCP, a Tor stream, or
+// anything else that reads and writes bytes.
+trait Transport: Read + Write + Send {}
+impl<T: Read + Write + Send> Transport for T {}
+
+/// A socket that optionally carries a TLS session on top of whichever
+/// transport it was built from. Callers only ever see `Read`/`Write`, so the
+/// rest of the tester doesn't have to care whether a given connection is
+/// encrypted, or what it's tunnelled through underneath.
+pub struct Socket(Box<dyn Transport>);
+
+impl Socket {
+    /// Wraps an already-connected `transport` in a TLS session according to
+    /// `config`, or returns it unchanged when `config.tls` is disabled.
+    pub fn wrap<T: Read + Write + Send + 'static>(
+        transport: T,
+        config: &SocketConfig,
+    ) -> io::Result<Socket> {
+        if !config.tls {
+            return Ok(Socket(Box::new(transport)));
+        }
+
+        let dns_name = server_name(config)?;
+        let tls_config = build_client_config(config, Vec::new());
+        let session = ClientSession::new(&Arc::new(tls_config), dns_name.as_ref());
+        Ok(Socket(Box::new(rustls::StreamOwned::new(
+            session, transport,
+        ))))
+    }
+}
+
+// Resolves the DNS name used for SNI and certificate verification, falling
+// back to the receiver's IP address when `--sni` isn't given.
+pub(crate) fn server_name(config: &SocketConfig) -> io::Result<webpki::DNSName> {
+    let server_name = config
+        .sni
+        .clone()
+        .unwrap_or_else(|| config.receiver.ip().to_string());
+
+    DNSNameRef::try_from_ascii_str(&server_name)
+        .map(DNSNameRef::to_owned)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid SNI hostname"))
+}
+
+// Builds the `rustls::ClientConfig` shared by every TLS-capable tester mode,
+// with `alpn_protocols` set for callers (like the HTTP/2 tester) that need
+// to negotiate a specific next protocol.
+pub(crate) fn build_client_config(config: &SocketConfig, alpn_protocols: Vec<Vec<u8>>) -> ClientConfig {
+    let mut tls_config = ClientConfig::new();
+    tls_config.alpn_protocols = alpn_protocols;
+
+    if config.insecure {
+        tls_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoCertVerification));
+    } else {
+        tls_config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    }
+
+    tls_config
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// Accepts any certificate presented by the server. Only wired in when the
+// user explicitly passes `--insecure`.
+struct NoCertVerification;
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
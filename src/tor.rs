@@ -0,0 +1,265 @@
+// finshir: A coroutines-driven Low & Slow traffic sender, written in Rust
+// Copyright (C) 2019  Temirkhan Myrzamadi <gymmasssorla@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For more information see <https://github.com/Gymmasssorla/finshir>.
+
+//! A pure-Rust Tor transport built on `arti-client`, replacing the old path
+//! that shelled out to a locally running Tor daemon through `tor-stream`.
+//! Building circuits is relatively expensive, so `testing::run` bootstraps
+//! exactly one `TorContext` and every coroutine borrows it to open its own
+//! stream.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arti_client::config::{BridgeConfigBuilder, TorClientConfigBuilder};
+use arti_client::{StreamPrefs, TorClient, TorClientConfig};
+use futures::future::{self, Either};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::pin_mut;
+use tor_rtcompat::{PreferredRuntime, Runtime, SleepProvider};
+
+pub struct TorContext {
+    client: TorClient<PreferredRuntime>,
+    worker: Arc<TorWorker>,
+}
+
+impl TorContext {
+    /// Bootstraps a Tor client, optionally seeded with `bridge_lines` (one
+    /// bridge specification per element, in the same format as `torrc`).
+    pub fn bootstrap(bridge_lines: &[String]) -> Result<TorContext, TorError> {
+        let runtime = PreferredRuntime::current().map_err(TorError::Runtime)?;
+
+        let mut config_builder = TorClientConfigBuilder::from_directories(
+            arti_client::config::default_cache_dir(),
+            arti_client::config::default_state_dir(),
+        );
+        for line in bridge_lines {
+            let bridge: BridgeConfigBuilder = line.parse().map_err(TorError::InvalidBridgeLine)?;
+            config_builder.bridges().bridges().push(bridge);
+        }
+        let config = config_builder.build().map_err(TorError::Config)?;
+
+        // One-time startup cost, happening before `testing::run` spawns any
+        // coroutine, so blocking the calling thread here doesn't stall
+        // anything -- only the per-connection operations below hand off to
+        // `TorWorker` to avoid that.
+        let client = runtime
+            .block_on(
+                TorClient::with_runtime(runtime.clone())
+                    .config(config)
+                    .create_bootstrapped(),
+            )
+            .map_err(TorError::Bootstrap)?;
+
+        let worker = Arc::new(TorWorker::spawn(runtime));
+
+        Ok(TorContext { client, worker })
+    }
+
+    /// Opens a new anonymized stream to `receiver`, honoring `connect_timeout`
+    /// the same way the plain non-Tor path does. When `isolate` is set, the
+    /// stream is tagged with a fresh isolation token, so it never shares a
+    /// circuit with another coroutine's connection -- this way a single-exit
+    /// block can't kill the whole run.
+    pub fn connect(
+        &self,
+        receiver: SocketAddr,
+        connect_timeout: Duration,
+        write_timeout: Duration,
+        isolate: bool,
+    ) -> io::Result<TorStream> {
+        let mut prefs = StreamPrefs::new();
+        if isolate {
+            prefs.new_isolation_group();
+        }
+
+        let client = self.client.clone();
+        let target = (receiver.ip().to_string(), receiver.port());
+        let worker = Arc::clone(&self.worker);
+
+        let inner = worker.run(move |runtime| {
+            runtime.block_on(async {
+                let connect_fut = client.connect_with_prefs(target, &prefs);
+                pin_mut!(connect_fut);
+                let sleep = runtime.sleep(connect_timeout);
+                pin_mut!(sleep);
+
+                match future::select(connect_fut, sleep).await {
+                    Either::Left((res, _)) => {
+                        res.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+                    }
+                    Either::Right(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the Tor operation timed out",
+                    )),
+                }
+            })
+        })?;
+
+        Ok(TorStream {
+            inner: Some(inner),
+            worker,
+            write_timeout,
+        })
+    }
+}
+
+// Runs every blocking Tor operation on a single dedicated OS thread, away
+// from `may`'s own scheduler threads. `may`'s whole concurrency model
+// assumes a coroutine never blocks its host OS thread, but `tor_rtcompat`'s
+// `block_on` does exactly that -- so instead of calling it from the
+// coroutine itself, `run` hands the work to this worker thread and waits on
+// a `may`-aware channel, which parks the *coroutine*, not the OS thread,
+// letting every other coroutine scheduled on it keep making progress.
+struct TorWorker {
+    jobs: std::sync::mpsc::Sender<Box<dyn FnOnce(&PreferredRuntime) + Send>>,
+}
+
+impl TorWorker {
+    fn spawn(runtime: PreferredRuntime) -> TorWorker {
+        let (jobs, receiver) =
+            std::sync::mpsc::channel::<Box<dyn FnOnce(&PreferredRuntime) + Send>>();
+
+        std::thread::spawn(move || {
+            for job in receiver {
+                job(&runtime);
+            }
+        });
+
+        TorWorker { jobs }
+    }
+
+    fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&PreferredRuntime) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = may::sync::mpsc::channel();
+
+        self.jobs
+            .send(Box::new(move |runtime| {
+                let _ = tx.send(f(runtime));
+            }))
+            .expect("the Tor worker thread has died");
+
+        rx.recv().expect("the Tor worker thread has died")
+    }
+}
+
+/// Bridges an Arti `DataStream` (asynchronous) onto the synchronous
+/// `Read`/`Write` traits the rest of the tester expects, by handing every
+/// call off to `TorWorker`, honoring `write_timeout` on every write the same
+/// way the plain non-Tor path does.
+pub struct TorStream {
+    // Taken out for the duration of each operation and handed to the
+    // worker thread by value, then put back -- `arti_client::DataStream`
+    // borrows can't cross the thread hand-off, but owned values can.
+    inner: Option<arti_client::DataStream>,
+    worker: Arc<TorWorker>,
+    write_timeout: Duration,
+}
+
+impl TorStream {
+    fn with_inner<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(arti_client::DataStream, &PreferredRuntime) -> (io::Result<T>, arti_client::DataStream)
+            + Send
+            + 'static,
+        T: Send + 'static,
+    {
+        let inner = self
+            .inner
+            .take()
+            .expect("a TorStream operation is already in flight");
+        let (result, inner) = self.worker.run(move |runtime| f(inner, runtime));
+        self.inner = Some(inner);
+        result
+    }
+}
+
+impl Read for TorStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len();
+        let (n, owned) = self.with_inner(move |mut inner, runtime| {
+            let mut owned = vec![0u8; len];
+            let result = runtime
+                .block_on(inner.read(&mut owned))
+                .map(|n| (n, owned));
+            (result, inner)
+        })?;
+
+        buf[..n].copy_from_slice(&owned[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for TorStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let owned = buf.to_vec();
+        let write_timeout = self.write_timeout;
+
+        self.with_inner(move |mut inner, runtime| {
+            let result = runtime.block_on(async {
+                let write_fut = inner.write(&owned);
+                pin_mut!(write_fut);
+                let sleep = runtime.sleep(write_timeout);
+                pin_mut!(sleep);
+
+                match future::select(write_fut, sleep).await {
+                    Either::Left((res, _)) => res,
+                    Either::Right(_) => {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"))
+                    }
+                }
+            });
+
+            (result, inner)
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_inner(|mut inner, runtime| {
+            let result = runtime.block_on(inner.flush());
+            (result, inner)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum TorError {
+    Runtime(Box<dyn Error>),
+    InvalidBridgeLine(Box<dyn Error>),
+    Config(Box<dyn Error>),
+    Bootstrap(Box<dyn Error>),
+}
+
+impl Display for TorError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            TorError::Runtime(err) => write!(fmt, "failed to obtain an async runtime: {}", err),
+            TorError::InvalidBridgeLine(err) => write!(fmt, "invalid bridge line: {}", err),
+            TorError::Config(err) => write!(fmt, "invalid Tor client configuration: {}", err),
+            TorError::Bootstrap(err) => write!(fmt, "failed to bootstrap the Tor client: {}", err),
+        }
+    }
+}
+
+impl Error for TorError {}